@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use {Instr, Loc, Source, Spec};
+use vm;
+
+pub trait Render {
+  fn start_node(&mut self, w: &mut dyn Write, row: usize, col: usize, node: Option<&vm::Node>) -> io::Result<()>;
+  fn instr(&mut self, w: &mut dyn Write, instr: &Instr, pos: usize) -> io::Result<()>;
+  fn label(&mut self, w: &mut dyn Write, label: &str) -> io::Result<()>;
+  fn end_node(&mut self, w: &mut dyn Write) -> io::Result<()>;
+
+  fn render(&mut self, w: &mut dyn Write, spec: &Spec, grid: Option<&vm::Grid>) -> io::Result<()> {
+    for program in &spec.programs {
+      let row = program.row;
+      let col = program.col;
+      let node = grid.and_then(|g| g.node(row, col));
+      try!(self.start_node(w, row, col, node));
+
+      let mut labels_at: HashMap<usize, Vec<&str>> = HashMap::new();
+      for (&label, &pos) in &program.labels {
+        labels_at.entry(pos).or_insert_with(Vec::new).push(label);
+      }
+
+      for (i, instr_and_pos) in program.instrs.iter().enumerate() {
+        if let Some(labels) = labels_at.get(&i) {
+          for label in labels {
+            try!(self.label(w, label));
+          }
+        }
+        try!(self.instr(w, &instr_and_pos.instr, instr_and_pos.pos));
+      }
+      if let Some(labels) = labels_at.get(&program.instrs.len()) {
+        for label in labels {
+          try!(self.label(w, label));
+        }
+      }
+
+      try!(self.end_node(w));
+    }
+    Ok(())
+  }
+}
+
+fn format_loc(loc: &Loc) -> &'static str {
+  match *loc {
+    Loc::Left => "LEFT",
+    Loc::Right => "RIGHT",
+    Loc::Up => "UP",
+    Loc::Down => "DOWN",
+    Loc::Any => "ANY",
+    Loc::Acc => "ACC",
+    Loc::Last => "LAST",
+  }
+}
+
+fn format_source(src: &Source) -> String {
+  match *src {
+    Source::Val(v) => v.to_string(),
+    Source::Loc(ref loc) => format_loc(loc).to_string(),
+  }
+}
+
+fn format_instr(instr: &Instr) -> String {
+  match *instr {
+    Instr::Nop => "NOP".to_string(),
+    Instr::Mov(ref src, ref dest) => format!("MOV {}, {}", format_source(src), format_loc(dest)),
+    Instr::Swp => "SWP".to_string(),
+    Instr::Sav => "SAV".to_string(),
+    Instr::Add(ref src) => format!("ADD {}", format_source(src)),
+    Instr::Sub(ref src) => format!("SUB {}", format_source(src)),
+    Instr::Neg => "NEG".to_string(),
+    Instr::Jmp(label) => format!("JMP {}", label),
+    Instr::Jez(label) => format!("JEZ {}", label),
+    Instr::Jnz(label) => format!("JNZ {}", label),
+    Instr::Jgz(label) => format!("JGZ {}", label),
+    Instr::Jlz(label) => format!("JLZ {}", label),
+    Instr::Jro(ref src) => format!("JRO {}", format_source(src)),
+    Instr::Comment(ref s) => s.clone(),
+    Instr::Emptyline => String::new(),
+  }
+}
+
+fn html_escape(s: &str) -> String {
+  s.chars().fold(String::with_capacity(s.len()), |mut acc, c| {
+    match c {
+      '&' => acc.push_str("&amp;"),
+      '<' => acc.push_str("&lt;"),
+      '>' => acc.push_str("&gt;"),
+      '"' => acc.push_str("&quot;"),
+      '\'' => acc.push_str("&#39;"),
+      _ => acc.push(c),
+    }
+    acc
+  })
+}
+
+fn format_last(last: Option<vm::Port>) -> &'static str {
+  match last {
+    None => "-",
+    Some(vm::Port::Up) => "UP",
+    Some(vm::Port::Down) => "DOWN",
+    Some(vm::Port::Left) => "LEFT",
+    Some(vm::Port::Right) => "RIGHT",
+  }
+}
+
+fn mode_name(mode: vm::Mode) -> &'static str {
+  match mode {
+    vm::Mode::Idle => "IDLE",
+    vm::Mode::Run => "RUN",
+    vm::Mode::Read(_) => "READ",
+    vm::Mode::Write(_) => "WRITE",
+  }
+}
+
+pub struct AsciiHandler {
+  pending_labels: Vec<String>,
+}
+
+impl AsciiHandler {
+  pub fn new() -> AsciiHandler {
+    AsciiHandler { pending_labels: Vec::new() }
+  }
+}
+
+impl Render for AsciiHandler {
+  fn start_node(&mut self, w: &mut dyn Write, row: usize, col: usize, node: Option<&vm::Node>) -> io::Result<()> {
+    try!(write!(w, "--- node ({}, {}) ---\n", row, col));
+    if let Some(node) = node {
+      try!(write!(w,
+                  "ACC: {:>4}  BAK: {:>4}  MODE: {:<5}  LAST: {}\n",
+                  node.acc,
+                  node.bak,
+                  mode_name(node.mode),
+                  format_last(node.last)));
+    }
+    Ok(())
+  }
+
+  fn label(&mut self, _w: &mut dyn Write, label: &str) -> io::Result<()> {
+    self.pending_labels.push(label.to_string());
+    Ok(())
+  }
+
+  fn instr(&mut self, w: &mut dyn Write, instr: &Instr, _pos: usize) -> io::Result<()> {
+    let labels = self.pending_labels.drain(..).collect::<Vec<_>>().join(", ");
+    if labels.is_empty() {
+      write!(w, "  {}\n", format_instr(instr))
+    } else {
+      write!(w, "{}: {}\n", labels, format_instr(instr))
+    }
+  }
+
+  fn end_node(&mut self, w: &mut dyn Write) -> io::Result<()> {
+    write!(w, "\n")
+  }
+}
+
+pub struct HtmlHandler {
+  pending_labels: Vec<String>,
+}
+
+impl HtmlHandler {
+  pub fn new() -> HtmlHandler {
+    HtmlHandler { pending_labels: Vec::new() }
+  }
+}
+
+impl Render for HtmlHandler {
+  fn start_node(&mut self, w: &mut dyn Write, row: usize, col: usize, node: Option<&vm::Node>) -> io::Result<()> {
+    try!(write!(w, "<table class=\"tis100-node\" data-row=\"{}\" data-col=\"{}\">\n", row, col));
+    if let Some(node) = node {
+      try!(write!(w,
+                  "<caption class=\"tis100-status tis100-{}\">ACC {} &middot; BAK {} &middot; {}</caption>\n",
+                  mode_name(node.mode).to_lowercase(),
+                  node.acc,
+                  node.bak,
+                  mode_name(node.mode)));
+    }
+    Ok(())
+  }
+
+  fn label(&mut self, _w: &mut dyn Write, label: &str) -> io::Result<()> {
+    self.pending_labels.push(html_escape(label));
+    Ok(())
+  }
+
+  fn instr(&mut self, w: &mut dyn Write, instr: &Instr, _pos: usize) -> io::Result<()> {
+    let labels = self.pending_labels.drain(..).collect::<Vec<_>>().join(", ");
+    write!(w,
+           "<tr><td class=\"label\">{}</td><td class=\"instr\">{}</td></tr>\n",
+           labels,
+           html_escape(&format_instr(instr)))
+  }
+
+  fn end_node(&mut self, w: &mut dyn Write) -> io::Result<()> {
+    write!(w, "</table>\n")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse_spec;
+
+  #[test]
+  fn test_ascii_handler_renders_node_and_instr() {
+    let spec = parse_spec("@0\nNOP\n\n".lines()).unwrap();
+    let mut out = Vec::new();
+    AsciiHandler::new().render(&mut out, &spec, None).unwrap();
+    let s = String::from_utf8(out).unwrap();
+    assert!(s.contains("--- node (0, 0) ---"));
+    assert!(s.contains("NOP"));
+  }
+
+  #[test]
+  fn test_html_handler_escapes_label() {
+    let spec = parse_spec("@0\nevil<script>:NOP\n\n".lines()).unwrap();
+    let mut out = Vec::new();
+    HtmlHandler::new().render(&mut out, &spec, None).unwrap();
+    let s = String::from_utf8(out).unwrap();
+    assert!(!s.contains("<script>"));
+    assert!(s.contains("&lt;script&gt;"));
+  }
+
+  #[test]
+  fn test_html_handler_escapes_comment() {
+    let mut out = Vec::new();
+    let mut handler = HtmlHandler::new();
+    handler.instr(&mut out, &Instr::Comment("# <b>hi</b>".to_string()), 0).unwrap();
+    let s = String::from_utf8(out).unwrap();
+    assert!(!s.contains("<b>"));
+    assert!(s.contains("&lt;b&gt;hi&lt;/b&gt;"));
+  }
+}
@@ -1,20 +1,32 @@
+use std::env;
+use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::process;
 use std::str::Lines;
 use std::str::SplitWhitespace;
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
+mod vm;
+mod export;
+mod verify;
+
+use export::Render;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Loc {
   Left,
   Right,
   Up,
   Down,
+  Any,
   Acc,
   Last,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Source {
   Val(i16),
   Loc(Loc),
@@ -45,10 +57,15 @@ pub enum Instr<'a> {
   Emptyline,
 }
 
+pub const ROWS: usize = 3;
+pub const COLS: usize = 4;
+
 #[derive(Debug)]
 struct Program<'a> {
   instrs: Vec<InstrAndPos<'a>>,
   labels: HashMap<&'a str, usize>,
+  row: usize,
+  col: usize,
 }
 
 #[derive(Debug)]
@@ -56,12 +73,19 @@ struct Spec<'a> {
   programs: Vec<Program<'a>>,
 }
 
+impl<'a> Spec<'a> {
+  pub fn node_at(&self, row: usize, col: usize) -> Option<&Program<'a>> {
+    self.programs.iter().find(|program| program.row == row && program.col == col)
+  }
+}
+
 pub fn parse_loc(s: &str) -> Result<Loc, String> {
   match s {
     "LEFT" => Ok(Loc::Left),
     "RIGHT" => Ok(Loc::Right),
     "UP" => Ok(Loc::Up),
     "DOWN" => Ok(Loc::Down),
+    "ANY" => Ok(Loc::Any),
     "ACC" => Ok(Loc::Acc),
     "LAST" => Ok(Loc::Last),
     s => Err(format!("Invalid loc {}", s)),
@@ -170,7 +194,7 @@ pub fn get_label(line: &str) -> (&str, Option<&str>) {
   }
 }
 
-fn parse_program<'a>(buf: Vec<&'a str>) -> Result<Program<'a>, String> {
+fn parse_program<'a>(buf: Vec<&'a str>, row: usize, col: usize) -> Result<Program<'a>, String> {
   let mut instrs = Vec::new();
   let mut labels = HashMap::new();
   for (line_no, line) in buf.iter().enumerate() {
@@ -194,58 +218,163 @@ fn parse_program<'a>(buf: Vec<&'a str>) -> Result<Program<'a>, String> {
       }
     }
   }
+  for instr_and_pos in &instrs {
+    let target = match instr_and_pos.instr {
+      Instr::Jmp(label) |
+      Instr::Jez(label) |
+      Instr::Jnz(label) |
+      Instr::Jgz(label) |
+      Instr::Jlz(label) => Some(label),
+      _ => None,
+    };
+    if let Some(label) = target {
+      if !labels.contains_key(label) {
+        return Err(format!("jump to undefined label '{}'", label));
+      }
+    }
+  }
+
   Ok(Program {
     instrs: instrs,
     labels: labels,
+    row: row,
+    col: col,
   })
 }
 
-fn parse_spec<'a>(buf: Lines<'a>) -> Result<Spec<'a>, String> {
-  let mut programs = Vec::new();
+fn close_section(raw_program: &mut Vec<&str>) -> Result<(), String> {
+  let last_line = try!(raw_program.pop().ok_or("invalid empty section".to_string()));
+  if !last_line.is_empty() {
+    return Err(format!("invalid section: didn't end with an empty line"));
+  }
+  Ok(())
+}
 
+fn parse_spec<'a>(buf: Lines<'a>) -> Result<Spec<'a>, String> {
   let mut buf = buf.into_iter();
   let first_line = try!(buf.next().ok_or("invalid empty spec"));
-  if first_line != "@0" {
-    return Err(format!("invalid spec header {}, expecting @0", first_line));
+  if !first_line.starts_with("@") {
+    return Err(format!("invalid spec header {}, expecting a section like @0", first_line));
+  }
+  let mut current_section = try!(first_line[1..].parse::<u8>().map_err(|e| e.to_string()));
+  if current_section > 11 {
+    return Err(format!("section {} greater than maximum 11", current_section));
   }
 
-  let mut next_section = 1;
   let mut raw_program: Vec<&str> = Vec::new();
-  let mut raw_programs = Vec::new();
+  let mut raw_programs: Vec<(u8, Vec<&str>)> = Vec::new();
   for line in buf {
     if line.starts_with("@") {
       let sec = try!(line[1..].parse::<u8>().map_err(|e| e.to_string()));
-      if sec != next_section {
-        return Err(format!("expecting section {}, found section {}", next_section, sec));
-      } else if sec > 11 {
+      if sec > 11 {
         return Err(format!("section {} greater than maximum 11", sec));
+      } else if sec <= current_section {
+        return Err(format!("expecting a section greater than {}, found section {}",
+                            current_section,
+                            sec));
       }
-      next_section = next_section + 1;
-      let last_line = try!(raw_program.pop().ok_or("invalid empty section".to_string()));
-      if !last_line.is_empty() {
-        return Err(format!("invalid section: didn't end with an empty line"));
-      }
-      print!("{:?}\n", raw_program);
-      raw_programs.push(raw_program);
+      try!(close_section(&mut raw_program));
+      raw_programs.push((current_section, raw_program));
       raw_program = Vec::new();
+      current_section = sec;
     } else {
       raw_program.push(line);
     }
   }
+  try!(close_section(&mut raw_program));
+  raw_programs.push((current_section, raw_program));
 
-  for raw_program in raw_programs {
-    programs.push(try!(parse_program(raw_program)))
+  let mut programs = Vec::new();
+  for (sec, raw_program) in raw_programs {
+    let sec = sec as usize;
+    programs.push(try!(parse_program(raw_program, sec / COLS, sec % COLS)));
   }
 
   Ok(Spec { programs: programs })
 }
 
-fn main() {
+fn read_file(path: &str) -> Result<String, String> {
+  let mut contents = String::new();
+  let mut file = try!(File::open(path).map_err(|e| format!("{}: {}", path, e)));
+  try!(file.read_to_string(&mut contents).map_err(|e| format!("{}: {}", path, e)));
+  Ok(contents)
+}
+
+fn cmd_dump() -> Result<(), String> {
   let mut buffer = String::new();
-  let stdin = io::stdin();
-  stdin.lock().read_to_string(&mut buffer).unwrap();
-  let spec = parse_spec(buffer.lines()).unwrap();
+  try!(io::stdin().lock().read_to_string(&mut buffer).map_err(|e| e.to_string()));
+  let spec = try!(parse_spec(buffer.lines()));
   println!("{:?}", spec);
+  Ok(())
+}
+
+fn cmd_render(args: &[String]) -> Result<(), String> {
+  let (html, rest) = match args.first().map(|s| s.as_str()) {
+    Some("--html") => (true, &args[1..]),
+    _ => (false, args),
+  };
+  let path = try!(rest.first().ok_or("render requires a program file".to_string()));
+  let program_src = try!(read_file(path));
+  let spec = try!(parse_spec(program_src.lines()));
+
+  let stdout = io::stdout();
+  let mut out = stdout.lock();
+  if html {
+    try!(export::HtmlHandler::new().render(&mut out, &spec, None).map_err(|e| e.to_string()));
+  } else {
+    try!(export::AsciiHandler::new().render(&mut out, &spec, None).map_err(|e| e.to_string()));
+  }
+  Ok(())
+}
+
+fn cmd_verify(args: &[String]) -> Result<(), String> {
+  let program_path = try!(args.get(0).ok_or("verify requires a program file".to_string()));
+  let puzzle_path = try!(args.get(1).ok_or("verify requires a puzzle file".to_string()));
+
+  let program_src = try!(read_file(program_path));
+  let spec = try!(parse_spec(program_src.lines()));
+  let puzzle_src = try!(read_file(puzzle_path));
+  let puzzle = try!(verify::parse_puzzle(puzzle_src.lines()));
+
+  let report = verify::run(&spec, &puzzle);
+  println!("cycles: {}", report.cycles);
+  println!("nodes with code: {}", report.nodes_with_code);
+  println!("instructions: {}", report.instruction_count);
+  if let Some(ref m) = report.mismatch {
+    println!("mismatch in '{}' at index {}: expected {:?}, got {}",
+             m.stream,
+             m.index,
+             m.expected,
+             m.produced);
+  }
+  println!("result: {}", if report.passed { "PASS" } else { "FAIL" });
+  Ok(())
+}
+
+fn cmd_batch(args: &[String]) -> Result<(), String> {
+  let dir_path = try!(args.get(0).ok_or("batch requires a directory of solutions".to_string()));
+  let puzzle_path = try!(args.get(1).ok_or("batch requires a puzzle file".to_string()));
+
+  let puzzle_src = try!(read_file(puzzle_path));
+  let puzzle = try!(verify::parse_puzzle(puzzle_src.lines()));
+
+  let stdout = io::stdout();
+  let mut out = stdout.lock();
+  verify::run_batch(Path::new(dir_path), &puzzle, &mut out).map_err(|e| e.to_string())
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let result = match args.first().map(|s| s.as_str()) {
+    Some("render") => cmd_render(&args[1..]),
+    Some("verify") => cmd_verify(&args[1..]),
+    Some("batch") => cmd_batch(&args[1..]),
+    _ => cmd_dump(),
+  };
+  if let Err(e) = result {
+    writeln!(io::stderr(), "error: {}", e).unwrap();
+    process::exit(1);
+  }
 }
 
 #[cfg(test)]
@@ -261,6 +390,7 @@ mod tests {
   #[test]
   fn test_parse_loc() {
     assert_eq!(Ok(Loc::Acc), parse_loc("ACC"));
+    assert_eq!(Ok(Loc::Any), parse_loc("ANY"));
     assert!(parse_loc("Aflkj").is_err());
   }
 
@@ -300,4 +430,30 @@ mod tests {
     assert_eq!(("label : nop", None), get_label("label : nop"));
     assert_eq!(("3: nop", Some("label")), get_label("label:3: nop"));
   }
+
+  #[test]
+  fn test_parse_spec_sparse() {
+    let spec = parse_spec("@0\nNOP\n\n@3\nSWP\n\n@7\nSAV\n\n".lines()).unwrap();
+    assert_eq!(3, spec.programs.len());
+    assert!(spec.node_at(0, 0).is_some());
+    assert_eq!((0, 0), (spec.node_at(0, 0).unwrap().row, spec.node_at(0, 0).unwrap().col));
+    assert_eq!((0, 3), (spec.node_at(0, 3).unwrap().row, spec.node_at(0, 3).unwrap().col));
+    assert_eq!((1, 3), (spec.node_at(1, 3).unwrap().row, spec.node_at(1, 3).unwrap().col));
+    assert!(spec.node_at(1, 0).is_none());
+  }
+
+  #[test]
+  fn test_parse_spec_rejects_duplicate_section() {
+    assert!(parse_spec("@0\nNOP\n\n@0\nSWP\n\n".lines()).is_err());
+  }
+
+  #[test]
+  fn test_parse_spec_rejects_out_of_range_section() {
+    assert!(parse_spec("@12\nNOP\n\n".lines()).is_err());
+  }
+
+  #[test]
+  fn test_parse_spec_rejects_undefined_jump_target() {
+    assert!(parse_spec("@0\nJMP NOPE\n\n".lines()).is_err());
+  }
 }
@@ -0,0 +1,301 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::str::Lines;
+use std::str::SplitWhitespace;
+
+use {parse_spec, Spec, COLS, ROWS};
+use vm;
+
+pub struct InputStream {
+  pub name: String,
+  pub col: usize,
+  pub values: Vec<i16>,
+}
+
+pub struct OutputStream {
+  pub name: String,
+  pub col: usize,
+  pub expected: Vec<i16>,
+}
+
+pub struct Puzzle {
+  pub inputs: Vec<InputStream>,
+  pub outputs: Vec<OutputStream>,
+  pub cycle_cap: u64,
+}
+
+impl Puzzle {
+  pub fn new(inputs: Vec<InputStream>,
+             outputs: Vec<OutputStream>,
+             cycle_cap: u64)
+             -> Result<Puzzle, String> {
+    for input in &inputs {
+      if input.col >= COLS {
+        return Err(format!("input '{}' column {} out of range", input.name, input.col));
+      }
+    }
+    for output in &outputs {
+      if output.col >= COLS {
+        return Err(format!("output '{}' column {} out of range", output.name, output.col));
+      }
+    }
+    Ok(Puzzle {
+      inputs: inputs,
+      outputs: outputs,
+      cycle_cap: cycle_cap,
+    })
+  }
+}
+
+fn parse_stream_values(words: &mut SplitWhitespace) -> Result<Vec<i16>, String> {
+  let mut values = Vec::new();
+  for word in words {
+    values.push(try!(word.parse::<i16>().map_err(|e| e.to_string())));
+  }
+  Ok(values)
+}
+
+pub fn parse_puzzle<'a>(buf: Lines<'a>) -> Result<Puzzle, String> {
+  let mut cycle_cap = None;
+  let mut inputs = Vec::new();
+  let mut outputs = Vec::new();
+
+  for line in buf {
+    if line.trim().is_empty() {
+      continue;
+    }
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("cycles") => {
+        let n = try!(words.next().ok_or("cycles directive missing value".to_string()));
+        cycle_cap = Some(try!(n.parse::<u64>().map_err(|e| e.to_string())));
+      }
+      Some("input") => {
+        let name = try!(words.next().ok_or("input directive missing name".to_string())).to_string();
+        let col = try!(try!(words.next().ok_or("input directive missing column".to_string()))
+          .parse::<usize>()
+          .map_err(|e| e.to_string()));
+        let values = try!(parse_stream_values(&mut words));
+        inputs.push(InputStream { name: name, col: col, values: values });
+      }
+      Some("output") => {
+        let name = try!(words.next().ok_or("output directive missing name".to_string())).to_string();
+        let col = try!(try!(words.next().ok_or("output directive missing column".to_string()))
+          .parse::<usize>()
+          .map_err(|e| e.to_string()));
+        let expected = try!(parse_stream_values(&mut words));
+        outputs.push(OutputStream { name: name, col: col, expected: expected });
+      }
+      Some(s) => return Err(format!("invalid puzzle directive '{}'", s)),
+      None => {}
+    }
+  }
+
+  let cycle_cap = try!(cycle_cap.ok_or("puzzle missing cycles directive".to_string()));
+  Puzzle::new(inputs, outputs, cycle_cap)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+  pub stream: String,
+  pub index: usize,
+  pub expected: Option<i16>,
+  pub produced: i16,
+}
+
+pub struct Report {
+  pub passed: bool,
+  pub cycles: u64,
+  pub nodes_with_code: usize,
+  pub instruction_count: usize,
+  pub mismatch: Option<Mismatch>,
+}
+
+pub fn run(spec: &Spec, puzzle: &Puzzle) -> Report {
+  let mut grid = vm::Grid::new(spec);
+  let mut input_pos = vec![0usize; puzzle.inputs.len()];
+  let mut produced: Vec<Vec<i16>> = puzzle.outputs.iter().map(|_| Vec::new()).collect();
+  let mut mismatch = None;
+
+  let nodes_with_code = spec.programs.iter().filter(|p| !p.instrs.is_empty()).count();
+  let instruction_count: usize = spec.programs.iter().map(|p| p.instrs.len()).sum();
+
+  loop {
+    let mut progressed = grid.step();
+
+    for (i, input) in puzzle.inputs.iter().enumerate() {
+      if input_pos[i] >= input.values.len() {
+        continue;
+      }
+      if let Some(node) = grid.node_mut(0, input.col) {
+        if let Some(vm::BoundaryOp::Read(vm::Port::Up)) = node.boundary_op(vm::Port::Up) {
+          node.fulfill_read(vm::Port::Up, input.values[input_pos[i]]);
+          input_pos[i] += 1;
+          progressed = true;
+        }
+      }
+    }
+
+    for (i, output) in puzzle.outputs.iter().enumerate() {
+      if let Some(node) = grid.node_mut(ROWS - 1, output.col) {
+        if let Some(vm::BoundaryOp::Write(vm::Port::Down, _)) = node.boundary_op(vm::Port::Down) {
+          let value = node.fulfill_write(vm::Port::Down);
+          let index = produced[i].len();
+          produced[i].push(value);
+          progressed = true;
+
+          if mismatch.is_none() {
+            match output.expected.get(index) {
+              Some(&expected) if expected == value => {}
+              Some(&expected) => {
+                mismatch = Some(Mismatch {
+                  stream: output.name.clone(),
+                  index: index,
+                  expected: Some(expected),
+                  produced: value,
+                });
+              }
+              None => {
+                mismatch = Some(Mismatch {
+                  stream: output.name.clone(),
+                  index: index,
+                  expected: None,
+                  produced: value,
+                });
+              }
+            }
+          }
+        }
+      }
+    }
+
+    let complete = puzzle.outputs
+      .iter()
+      .enumerate()
+      .all(|(i, output)| produced[i].len() >= output.expected.len());
+
+    if mismatch.is_some() || complete || grid.cycle() >= puzzle.cycle_cap || !progressed {
+      break;
+    }
+  }
+
+  let passed = mismatch.is_none() &&
+               puzzle.outputs.iter().enumerate().all(|(i, output)| produced[i] == output.expected);
+
+  Report {
+    passed: passed,
+    cycles: grid.cycle(),
+    nodes_with_code: nodes_with_code,
+    instruction_count: instruction_count,
+    mismatch: mismatch,
+  }
+}
+
+pub fn run_batch(dir: &Path, puzzle: &Puzzle, w: &mut dyn Write) -> io::Result<()> {
+  let mut paths: Vec<_> = try!(fs::read_dir(dir)).filter_map(|e| e.ok().map(|e| e.path())).collect();
+  paths.sort();
+
+  try!(write!(w,
+              "{:<24} {:>8} {:>8} {:>8} {:>6}\n",
+              "solution",
+              "cycles",
+              "nodes",
+              "instrs",
+              "result"));
+  for path in paths {
+    let name = path.file_name().map_or_else(|| "?".to_string(), |n| n.to_string_lossy().into_owned());
+    let mut contents = String::new();
+    let mut file = match fs::File::open(&path) {
+      Ok(file) => file,
+      Err(e) => {
+        try!(write!(w, "{:<24} error opening file: {}\n", name, e));
+        continue;
+      }
+    };
+    if let Err(e) = file.read_to_string(&mut contents) {
+      try!(write!(w, "{:<24} error reading file: {}\n", name, e));
+      continue;
+    }
+    match parse_spec(contents.lines()) {
+      Ok(spec) => {
+        let report = run(&spec, puzzle);
+        try!(write!(w,
+                    "{:<24} {:>8} {:>8} {:>8} {:>6}\n",
+                    name,
+                    report.cycles,
+                    report.nodes_with_code,
+                    report.instruction_count,
+                    if report.passed { "PASS" } else { "FAIL" }));
+      }
+      Err(e) => {
+        try!(write!(w, "{:<24} invalid spec: {}\n", name, e));
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse_spec;
+
+  const PASSTHROUGH_COLUMN: &'static str = "@0\nMOV UP, DOWN\n\n@4\nMOV UP, DOWN\n\n@8\nMOV UP, DOWN\n\n";
+
+  #[test]
+  fn test_run_passes_when_output_matches() {
+    let spec = parse_spec(PASSTHROUGH_COLUMN.lines()).unwrap();
+    let puzzle = Puzzle::new(vec![InputStream { name: "in".to_string(), col: 0, values: vec![1, 2, 3] }],
+                              vec![OutputStream { name: "out".to_string(), col: 0, expected: vec![1, 2, 3] }],
+                              50)
+      .unwrap();
+    let report = run(&spec, &puzzle);
+    assert!(report.passed);
+    assert!(report.mismatch.is_none());
+  }
+
+  #[test]
+  fn test_run_reports_mismatch() {
+    let spec = parse_spec(PASSTHROUGH_COLUMN.lines()).unwrap();
+    let puzzle = Puzzle::new(vec![InputStream { name: "in".to_string(), col: 0, values: vec![1] }],
+                              vec![OutputStream { name: "out".to_string(), col: 0, expected: vec![9] }],
+                              50)
+      .unwrap();
+    let report = run(&spec, &puzzle);
+    assert!(!report.passed);
+    assert_eq!(Some(Mismatch {
+                 stream: "out".to_string(),
+                 index: 0,
+                 expected: Some(9),
+                 produced: 1,
+               }),
+               report.mismatch);
+  }
+
+  #[test]
+  fn test_run_rendezvous_boundary_through_any_port() {
+    let spec = parse_spec("@0\nMOV ANY, DOWN\n\n@4\nMOV UP, DOWN\n\n@8\nMOV UP, ANY\n\n".lines()).unwrap();
+    let puzzle = Puzzle::new(vec![InputStream { name: "in".to_string(), col: 0, values: vec![1, 2, 3] }],
+                              vec![OutputStream { name: "out".to_string(), col: 0, expected: vec![1, 2, 3] }],
+                              50)
+      .unwrap();
+    let report = run(&spec, &puzzle);
+    assert!(report.passed);
+    assert!(report.mismatch.is_none());
+  }
+
+  #[test]
+  fn test_run_fails_when_output_incomplete() {
+    let spec = parse_spec(PASSTHROUGH_COLUMN.lines()).unwrap();
+    let puzzle = Puzzle::new(vec![InputStream { name: "in".to_string(), col: 0, values: vec![1] }],
+                              vec![OutputStream { name: "out".to_string(), col: 0, expected: vec![1, 2] }],
+                              50)
+      .unwrap();
+    let report = run(&spec, &puzzle);
+    assert!(!report.passed);
+    assert!(report.mismatch.is_none());
+  }
+}
@@ -0,0 +1,538 @@
+use std::mem;
+
+use {Instr, Loc, Program, Source, Spec, COLS, ROWS};
+
+pub const GRID_SIZE: usize = ROWS * COLS;
+
+pub const ACC_MIN: i16 = -999;
+pub const ACC_MAX: i16 = 999;
+
+fn clamp_acc(v: i32) -> i16 {
+  if v > ACC_MAX as i32 {
+    ACC_MAX
+  } else if v < ACC_MIN as i32 {
+    ACC_MIN
+  } else {
+    v as i16
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Port {
+  Up,
+  Down,
+  Left,
+  Right,
+}
+
+impl Port {
+  fn opposite(self) -> Port {
+    match self {
+      Port::Up => Port::Down,
+      Port::Down => Port::Up,
+      Port::Left => Port::Right,
+      Port::Right => Port::Left,
+    }
+  }
+}
+
+const ALL_PORTS: [Port; 4] = [Port::Left, Port::Right, Port::Up, Port::Down];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PortSpec {
+  Fixed(Port),
+  Any,
+  Unresolved,
+}
+
+impl PortSpec {
+  fn candidates(self) -> &'static [Port] {
+    match self {
+      PortSpec::Fixed(Port::Left) => &[Port::Left],
+      PortSpec::Fixed(Port::Right) => &[Port::Right],
+      PortSpec::Fixed(Port::Up) => &[Port::Up],
+      PortSpec::Fixed(Port::Down) => &[Port::Down],
+      PortSpec::Any => &ALL_PORTS,
+      PortSpec::Unresolved => &[],
+    }
+  }
+
+  fn placeholder(self) -> Port {
+    self.candidates().first().cloned().unwrap_or(Port::Up)
+  }
+
+  fn placeholder_or(self, fallback: Port) -> Port {
+    match self {
+      PortSpec::Unresolved => fallback,
+      other => other.placeholder(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+  Idle,
+  Run,
+  Read(Port),
+  Write(Port),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ReadDest {
+  StoreAcc,
+  AddAcc,
+  SubAcc,
+  Jro,
+  Write(Loc),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+  Exec,
+  AwaitRead { port: PortSpec, dest: ReadDest },
+  AwaitWrite { port: PortSpec, value: i16 },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Step<'a> {
+  Nop,
+  Swp,
+  Sav,
+  Neg,
+  Add(Source),
+  Sub(Source),
+  Jro(Source),
+  Jmp(&'a str),
+  Jez(&'a str),
+  Jnz(&'a str),
+  Jgz(&'a str),
+  Jlz(&'a str),
+  Mov(Source, Loc),
+}
+
+#[derive(Debug)]
+pub struct Node<'a> {
+  program: &'a Program<'a>,
+  pub acc: i16,
+  pub bak: i16,
+  ip: usize,
+  pub last: Option<Port>,
+  stage: Stage,
+  pub mode: Mode,
+  progressed: bool,
+}
+
+impl<'a> Node<'a> {
+  fn new(program: &'a Program<'a>) -> Node<'a> {
+    Node {
+      program: program,
+      acc: 0,
+      bak: 0,
+      ip: 0,
+      last: None,
+      stage: Stage::Exec,
+      mode: Mode::Idle,
+      progressed: false,
+    }
+  }
+
+  fn len(&self) -> usize {
+    self.program.instrs.len()
+  }
+
+  fn advance_ip(&mut self) {
+    self.ip += 1;
+    if self.ip >= self.len() {
+      self.ip = 0;
+    }
+  }
+
+  fn immediate(&self, src: &Source) -> i16 {
+    match *src {
+      Source::Val(v) => v,
+      Source::Loc(Loc::Acc) => self.acc,
+      Source::Loc(_) => unreachable!("port sources must be resolved through a rendezvous"),
+    }
+  }
+
+  fn resolve_port(&self, loc: &Loc) -> PortSpec {
+    match *loc {
+      Loc::Up => PortSpec::Fixed(Port::Up),
+      Loc::Down => PortSpec::Fixed(Port::Down),
+      Loc::Left => PortSpec::Fixed(Port::Left),
+      Loc::Right => PortSpec::Fixed(Port::Right),
+      Loc::Any => PortSpec::Any,
+      Loc::Last => self.last.map_or(PortSpec::Unresolved, PortSpec::Fixed),
+      Loc::Acc => PortSpec::Unresolved,
+    }
+  }
+
+  fn finish_noport(&mut self) {
+    self.advance_ip();
+    self.mode = Mode::Run;
+    self.progressed = true;
+  }
+
+  fn finish_port(&mut self, port: Port) {
+    self.last = Some(port);
+    self.advance_ip();
+    self.stage = Stage::Exec;
+    self.mode = Mode::Run;
+    self.progressed = true;
+  }
+
+  fn jump_to(&mut self, label: &'a str) {
+    self.ip = *self.program
+      .labels
+      .get(label)
+      .expect("parse_program validates that every jump target resolves");
+    self.mode = Mode::Run;
+    self.progressed = true;
+  }
+
+  fn jump_relative(&mut self, offset: i16) {
+    let len = self.len() as isize;
+    let target = self.ip as isize + offset as isize;
+    self.ip = if target < 0 {
+      0
+    } else if target >= len {
+      len - 1
+    } else {
+      target
+    } as usize;
+  }
+
+  fn exec_arith(&mut self, src: Source, dest: ReadDest) {
+    match src {
+      Source::Loc(ref loc) if *loc != Loc::Acc => {
+        let port = self.resolve_port(loc);
+        self.stage = Stage::AwaitRead { port: port, dest: dest };
+        self.mode = Mode::Read(port.placeholder());
+      }
+      _ => {
+        let value = self.immediate(&src);
+        match dest {
+          ReadDest::AddAcc => self.acc = clamp_acc(self.acc as i32 + value as i32),
+          ReadDest::SubAcc => self.acc = clamp_acc(self.acc as i32 - value as i32),
+          _ => unreachable!("exec_arith only used for ADD/SUB"),
+        }
+        self.finish_noport();
+      }
+    }
+  }
+
+  fn exec_jro(&mut self, src: Source) {
+    match src {
+      Source::Loc(ref loc) if *loc != Loc::Acc => {
+        let port = self.resolve_port(loc);
+        self.stage = Stage::AwaitRead { port: port, dest: ReadDest::Jro };
+        self.mode = Mode::Read(port.placeholder());
+      }
+      _ => {
+        let value = self.immediate(&src);
+        self.jump_relative(value);
+        self.mode = Mode::Run;
+        self.progressed = true;
+      }
+    }
+  }
+
+  fn exec_mov(&mut self, src: Source, dest: Loc) {
+    let reads_port = match src {
+      Source::Loc(ref loc) => *loc != Loc::Acc,
+      Source::Val(_) => false,
+    };
+    if reads_port {
+      if let Source::Loc(ref loc) = src {
+        let read_dest = if dest == Loc::Acc { ReadDest::StoreAcc } else { ReadDest::Write(dest) };
+        let port = self.resolve_port(loc);
+        self.stage = Stage::AwaitRead { port: port, dest: read_dest };
+        self.mode = Mode::Read(port.placeholder());
+      }
+    } else {
+      let value = self.immediate(&src);
+      if dest == Loc::Acc {
+        self.acc = value;
+        self.finish_noport();
+      } else {
+        let port = self.resolve_port(&dest);
+        self.stage = Stage::AwaitWrite { port: port, value: value };
+        self.mode = Mode::Write(port.placeholder());
+      }
+    }
+  }
+
+  fn exec(&mut self, step: Step<'a>) {
+    match step {
+      Step::Nop => self.finish_noport(),
+      Step::Swp => {
+        mem::swap(&mut self.acc, &mut self.bak);
+        self.finish_noport();
+      }
+      Step::Sav => {
+        self.bak = self.acc;
+        self.finish_noport();
+      }
+      Step::Neg => {
+        self.acc = clamp_acc(-(self.acc as i32));
+        self.finish_noport();
+      }
+      Step::Add(src) => self.exec_arith(src, ReadDest::AddAcc),
+      Step::Sub(src) => self.exec_arith(src, ReadDest::SubAcc),
+      Step::Jro(src) => self.exec_jro(src),
+      Step::Jmp(label) => self.jump_to(label),
+      Step::Jez(label) => if self.acc == 0 { self.jump_to(label) } else { self.finish_noport() },
+      Step::Jnz(label) => if self.acc != 0 { self.jump_to(label) } else { self.finish_noport() },
+      Step::Jgz(label) => if self.acc > 0 { self.jump_to(label) } else { self.finish_noport() },
+      Step::Jlz(label) => if self.acc < 0 { self.jump_to(label) } else { self.finish_noport() },
+      Step::Mov(src, dest) => self.exec_mov(src, dest),
+    }
+  }
+
+  fn begin_cycle(&mut self) {
+    if self.len() == 0 {
+      self.mode = Mode::Idle;
+      return;
+    }
+    let step = match self.program.instrs[self.ip].instr {
+      Instr::Nop => Step::Nop,
+      Instr::Swp => Step::Swp,
+      Instr::Sav => Step::Sav,
+      Instr::Neg => Step::Neg,
+      Instr::Add(src) => Step::Add(src),
+      Instr::Sub(src) => Step::Sub(src),
+      Instr::Jro(src) => Step::Jro(src),
+      Instr::Jmp(label) => Step::Jmp(label),
+      Instr::Jez(label) => Step::Jez(label),
+      Instr::Jnz(label) => Step::Jnz(label),
+      Instr::Jgz(label) => Step::Jgz(label),
+      Instr::Jlz(label) => Step::Jlz(label),
+      Instr::Mov(src, dest) => Step::Mov(src, dest),
+      Instr::Comment(_) | Instr::Emptyline => unreachable!("comments are filtered out while parsing"),
+    };
+    self.exec(step);
+  }
+
+  fn complete_read(&mut self, port: Port, value: i16) {
+    let dest = match self.stage {
+      Stage::AwaitRead { dest, .. } => dest,
+      _ => unreachable!("complete_read called on a node that isn't awaiting a read"),
+    };
+    match dest {
+      ReadDest::Write(loc) => {
+        let write_port = self.resolve_port(&loc);
+        self.last = Some(port);
+        self.stage = Stage::AwaitWrite { port: write_port, value: value };
+        self.mode = Mode::Write(write_port.placeholder_or(port));
+      }
+      ReadDest::StoreAcc => {
+        self.acc = value;
+        self.finish_port(port);
+      }
+      ReadDest::AddAcc => {
+        self.acc = clamp_acc(self.acc as i32 + value as i32);
+        self.finish_port(port);
+      }
+      ReadDest::SubAcc => {
+        self.acc = clamp_acc(self.acc as i32 - value as i32);
+        self.finish_port(port);
+      }
+      ReadDest::Jro => {
+        self.jump_relative(value);
+        self.last = Some(port);
+        self.stage = Stage::Exec;
+        self.mode = Mode::Run;
+        self.progressed = true;
+      }
+    }
+  }
+
+  fn complete_write(&mut self, port: Port) {
+    self.finish_port(port);
+  }
+
+  // `boundary_port` is the direction the grid boundary sits in (e.g. `Up` for a
+  // puzzle input feeding row 0). A node is boundary-eligible there whenever that
+  // direction is one of its current port candidates, so `ANY`/resolved-`LAST`
+  // reads and writes can rendezvous with the boundary just like they would with
+  // an in-grid neighbor in `Grid::step`.
+  pub fn boundary_op(&self, boundary_port: Port) -> Option<BoundaryOp> {
+    match self.stage {
+      Stage::AwaitRead { port, .. } if port.candidates().contains(&boundary_port) => {
+        Some(BoundaryOp::Read(boundary_port))
+      }
+      Stage::AwaitWrite { port, value } if port.candidates().contains(&boundary_port) => {
+        Some(BoundaryOp::Write(boundary_port, value))
+      }
+      _ => None,
+    }
+  }
+
+  pub fn fulfill_read(&mut self, boundary_port: Port, value: i16) {
+    match self.stage {
+      Stage::AwaitRead { port, .. } if port.candidates().contains(&boundary_port) => {
+        self.complete_read(boundary_port, value)
+      }
+      _ => panic!("fulfill_read called on a node without a pending read on that port"),
+    }
+  }
+
+  pub fn fulfill_write(&mut self, boundary_port: Port) -> i16 {
+    match self.stage {
+      Stage::AwaitWrite { port, value } if port.candidates().contains(&boundary_port) => {
+        self.complete_write(boundary_port);
+        value
+      }
+      _ => panic!("fulfill_write called on a node without a pending write on that port"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundaryOp {
+  Read(Port),
+  Write(Port, i16),
+}
+
+#[derive(Debug)]
+pub struct Grid<'a> {
+  nodes: Vec<Option<Node<'a>>>,
+  cycle: u64,
+}
+
+impl<'a> Grid<'a> {
+  pub fn new(spec: &'a Spec<'a>) -> Grid<'a> {
+    let mut nodes = Vec::with_capacity(GRID_SIZE);
+    for row in 0..ROWS {
+      for col in 0..COLS {
+        nodes.push(spec.node_at(row, col).map(Node::new));
+      }
+    }
+    Grid { nodes: nodes, cycle: 0 }
+  }
+
+  pub fn cycle(&self) -> u64 {
+    self.cycle
+  }
+
+  pub fn node(&self, row: usize, col: usize) -> Option<&Node<'a>> {
+    self.nodes[row * COLS + col].as_ref()
+  }
+
+  pub fn node_mut(&mut self, row: usize, col: usize) -> Option<&mut Node<'a>> {
+    self.nodes[row * COLS + col].as_mut()
+  }
+
+  fn neighbor(&self, idx: usize, port: Port) -> Option<usize> {
+    let row = idx / COLS;
+    let col = idx % COLS;
+    let (nrow, ncol) = match port {
+      Port::Up => if row == 0 { return None } else { (row - 1, col) },
+      Port::Down => if row + 1 >= ROWS { return None } else { (row + 1, col) },
+      Port::Left => if col == 0 { return None } else { (row, col - 1) },
+      Port::Right => if col + 1 >= COLS { return None } else { (row, col + 1) },
+    };
+    Some(nrow * COLS + ncol)
+  }
+
+  pub fn step(&mut self) -> bool {
+    for slot in self.nodes.iter_mut() {
+      if let Some(node) = slot.as_mut() {
+        node.progressed = false;
+        if let Stage::Exec = node.stage {
+          node.begin_cycle();
+        }
+      }
+    }
+
+    let mut writers = Vec::new();
+    let mut reader_spec = vec![None; self.nodes.len()];
+    for (idx, slot) in self.nodes.iter().enumerate() {
+      if let Some(ref node) = *slot {
+        match node.stage {
+          Stage::AwaitWrite { port, value } => writers.push((idx, port, value)),
+          Stage::AwaitRead { port, .. } => reader_spec[idx] = Some(port),
+          _ => {}
+        }
+      }
+    }
+
+    let mut reader_taken = vec![false; self.nodes.len()];
+    for &(widx, wspec, value) in &writers {
+      for &wport in wspec.candidates() {
+        let ridx = match self.neighbor(widx, wport) {
+          Some(ridx) => ridx,
+          None => continue,
+        };
+        if reader_taken[ridx] {
+          continue;
+        }
+        let rport = wport.opposite();
+        let matches = reader_spec[ridx].map_or(false, |rspec: PortSpec| rspec.candidates().contains(&rport));
+        if matches {
+          reader_taken[ridx] = true;
+          if let Some(node) = self.nodes[widx].as_mut() {
+            node.complete_write(wport);
+          }
+          if let Some(node) = self.nodes[ridx].as_mut() {
+            node.complete_read(rport, value);
+          }
+          break;
+        }
+      }
+    }
+
+    let mut progressed = false;
+    for slot in self.nodes.iter() {
+      if let Some(ref node) = *slot {
+        if node.progressed {
+          progressed = true;
+        }
+      }
+    }
+
+    self.cycle += 1;
+    progressed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse_spec;
+
+  #[test]
+  fn test_clamp_acc_saturates() {
+    assert_eq!(ACC_MAX, clamp_acc(5000));
+    assert_eq!(ACC_MIN, clamp_acc(-5000));
+    assert_eq!(500, clamp_acc(500));
+  }
+
+  #[test]
+  fn test_basic_rendezvous_relay() {
+    let spec = parse_spec("@0\nMOV 3, RIGHT\n\n@1\nMOV LEFT, ACC\n\n".lines()).unwrap();
+    let mut grid = Grid::new(&spec);
+    grid.step();
+    assert_eq!(3, grid.node(0, 1).unwrap().acc);
+  }
+
+  #[test]
+  fn test_any_port_resolves_to_available_neighbor() {
+    let spec = parse_spec("@0\nMOV 7, ANY\n\n@4\nMOV UP, ACC\n\n".lines()).unwrap();
+    let mut grid = Grid::new(&spec);
+    grid.step();
+    assert_eq!(7, grid.node(1, 0).unwrap().acc);
+  }
+
+  #[test]
+  fn test_jump_relative_clamps_to_program_bounds() {
+    let spec = parse_spec("@0\nNOP\nNOP\nNOP\n\n".lines()).unwrap();
+    let mut grid = Grid::new(&spec);
+    let node = grid.node_mut(0, 0).unwrap();
+    node.jump_relative(100);
+    assert_eq!(2, node.ip);
+    node.ip = 0;
+    node.jump_relative(-100);
+    assert_eq!(0, node.ip);
+  }
+}